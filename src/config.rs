@@ -0,0 +1,166 @@
+use std::{collections::HashSet, env, fs, path::PathBuf};
+
+use serde::Deserialize;
+
+use nix::unistd::Pid;
+
+use crate::{
+    logging::{LogLevel, log},
+    portal::{HYPR_PORTAL, XDG_PORTAL, spawn_portal, wait_for_service},
+    process::kill_process_tree,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    Never,
+    OnFailure,
+    Always,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ManagedService {
+    pub name: String,
+    pub path: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub priority: i32,
+    #[serde(default)]
+    pub after: Vec<String>,
+    #[serde(default = "default_restart_policy")]
+    pub restart: RestartPolicy,
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+}
+
+fn default_restart_policy() -> RestartPolicy {
+    RestartPolicy::OnFailure
+}
+
+fn default_max_retries() -> u32 {
+    3
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Config {
+    #[serde(default, rename = "service")]
+    pub services: Vec<ManagedService>,
+}
+
+// $XDG_CONFIG_HOME/portal-reset/config.toml, falling back to ~/.config.
+pub fn config_path() -> PathBuf {
+    let base = env::var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| {
+            let home = env::var("HOME").unwrap_or_else(|_| "/root".to_string());
+            PathBuf::from(home).join(".config")
+        });
+    base.join("portal-reset").join("config.toml")
+}
+
+// Loads the managed-service list from the user's config file, falling back
+// to the built-in Hyprland + XDG portal pair (the same ordering the tool
+// has always used) when the file is missing or fails to parse.
+pub fn load() -> Config {
+    let path = config_path();
+    match fs::read_to_string(&path) {
+        Ok(contents) => match toml::from_str::<Config>(&contents) {
+            Ok(config) => {
+                log(LogLevel::Info, &format!("Loaded config from {}", path.display()));
+                config
+            }
+            Err(e) => {
+                log(LogLevel::Error, &format!("Failed to parse {}: {}, using built-in defaults", path.display(), e));
+                default_config()
+            }
+        },
+        Err(_) => default_config(),
+    }
+}
+
+fn default_config() -> Config {
+    Config {
+        services: vec![
+            ManagedService {
+                name: "hyprland-portal".to_string(),
+                path: HYPR_PORTAL.to_string(),
+                args: Vec::new(),
+                priority: 0,
+                after: Vec::new(),
+                restart: RestartPolicy::OnFailure,
+                max_retries: 3,
+            },
+            ManagedService {
+                name: "xdg-portal".to_string(),
+                path: XDG_PORTAL.to_string(),
+                args: Vec::new(),
+                priority: 10,
+                after: vec!["hyprland-portal".to_string()],
+                restart: RestartPolicy::OnFailure,
+                max_retries: 3,
+            },
+        ],
+    }
+}
+
+// How many times to retry spawning a service before giving up on it (and on
+// anything in `after` that depends on it). Mirrors the retry-then-abort
+// behaviour the old fixed-iteration startup had for the Hyprland portal.
+pub const SERVICE_START_ATTEMPTS: u32 = 3;
+
+// Spawns `service`, waiting for it to report healthy, retrying up to
+// SERVICE_START_ATTEMPTS times. Shared by `run_reset`'s one-shot startup and
+// the supervisor's `spawn_all` so the gate-and-retry logic (and the handling
+// of a timed-out attempt's still-running process) only exists once.
+// An attempt that times out is killed before the next one is spawned, rather
+// than left running unmanaged and unreaped. Returns the pid of the attempt
+// that came up, or `None` if every attempt failed. A missing binary returns
+// `None` after the first attempt, without retrying.
+pub fn start_service(service: &ManagedService) -> nix::Result<Option<Pid>> {
+    for attempt in 1..=SERVICE_START_ATTEMPTS {
+        let Some(pid) = spawn_portal(&service.path, &service.name, &service.args)? else {
+            return Ok(None);
+        };
+
+        if wait_for_service(&service.path, 20) {
+            return Ok(Some(pid));
+        }
+
+        log(LogLevel::Warning, &format!(
+            "{} failed to come up (attempt {}/{}), killing it before retrying",
+            service.name, attempt, SERVICE_START_ATTEMPTS
+        ));
+        kill_process_tree(pid, true);
+    }
+    Ok(None)
+}
+
+// Kahn's algorithm over `after`, ties broken by ascending priority. Returns
+// an error naming every service left unordered (the cycle and anything
+// waiting on it) instead of silently dropping services.
+pub fn topological_order(services: &[ManagedService]) -> Result<Vec<ManagedService>, String> {
+    let names: HashSet<&str> = services.iter().map(|s| s.name.as_str()).collect();
+    let mut remaining: Vec<ManagedService> = services.to_vec();
+    let mut ordered = Vec::with_capacity(services.len());
+    let mut started: HashSet<String> = HashSet::new();
+
+    while !remaining.is_empty() {
+        remaining.sort_by_key(|s| s.priority);
+
+        let ready_index = remaining.iter().position(|s| {
+            s.after.iter().all(|dep| !names.contains(dep.as_str()) || started.contains(dep))
+        });
+
+        let Some(index) = ready_index else {
+            let stuck: Vec<&str> = remaining.iter().map(|s| s.name.as_str()).collect();
+            return Err(format!("dependency cycle among services: {}", stuck.join(", ")));
+        };
+
+        let service = remaining.remove(index);
+        started.insert(service.name.clone());
+        ordered.push(service);
+    }
+
+    Ok(ordered)
+}
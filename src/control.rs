@@ -0,0 +1,126 @@
+use std::{
+    env,
+    fs,
+    io::{BufRead, BufReader, Write},
+    os::unix::{fs::PermissionsExt, net::{UnixListener, UnixStream}},
+    path::PathBuf,
+    sync::mpsc,
+    thread,
+};
+
+use nix::unistd::Uid;
+
+use crate::logging::{LogLevel, log};
+
+#[derive(Debug)]
+pub enum ControlCommand {
+    Status,
+    Reset,
+    Restart(String),
+    Stop,
+}
+
+pub struct ControlRequest {
+    pub command: ControlCommand,
+    pub reply: mpsc::Sender<String>,
+}
+
+// $XDG_RUNTIME_DIR/portal-reset.sock, falling back to /tmp when the runtime
+// dir isn't set (e.g. running outside a login session).
+pub fn socket_path() -> PathBuf {
+    let base = env::var("XDG_RUNTIME_DIR").unwrap_or_else(|_| "/tmp".to_string());
+    PathBuf::from(base).join("portal-reset.sock")
+}
+
+// Binds the control socket and spawns a thread that accepts connections and
+// forwards newline-delimited commands to the supervisor over `tx`, mirroring
+// einhyrningsins' ctrl_path listener. Each connection gets its own reader
+// thread so a slow or silent client can't block other commands.
+//
+// On the /tmp fallback path the socket would otherwise be created with the
+// umask's default permissions (world-readable/writable on many systems), so
+// any local user could `stop`/`reset`/`restart` another user's supervisor.
+// We tighten the file mode to owner-only right after bind, and still check
+// each connection's peer UID in `handle_connection` as a second line of
+// defense in case something loosens that mode later.
+pub fn spawn_listener(tx: mpsc::Sender<ControlRequest>) -> nix::Result<PathBuf> {
+    let path = socket_path();
+    let _ = std::fs::remove_file(&path);
+
+    let listener = UnixListener::bind(&path).map_err(|_| nix::errno::Errno::EADDRINUSE)?;
+    let _ = fs::set_permissions(&path, fs::Permissions::from_mode(0o600));
+    log(LogLevel::Info, &format!("Control socket listening at {}", path.display()));
+
+    let result_path = path.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming().filter_map(Result::ok) {
+            let tx = tx.clone();
+            thread::spawn(move || handle_connection(stream, tx));
+        }
+    });
+
+    Ok(result_path)
+}
+
+// Rejects connections from any user other than the one running the
+// supervisor. The tightened socket mode (see `spawn_listener`) should
+// already keep other users out, but this is what actually stops a command
+// on the /tmp fallback path if the mode is ever widened again (e.g. by a
+// umask override).
+fn peer_is_us(stream: &UnixStream) -> bool {
+    match nix::sys::socket::getsockopt(stream, nix::sys::socket::sockopt::PeerCredentials) {
+        Ok(cred) => Uid::from_raw(cred.uid()) == nix::unistd::getuid(),
+        Err(_) => false,
+    }
+}
+
+fn handle_connection(stream: UnixStream, tx: mpsc::Sender<ControlRequest>) {
+    let mut writer = match stream.try_clone() {
+        Ok(w) => w,
+        Err(_) => return,
+    };
+
+    if !peer_is_us(&writer) {
+        log(LogLevel::Warning, "Rejected control connection from another user");
+        let _ = writeln!(writer, "ERROR permission denied");
+        return;
+    }
+
+    let reader = BufReader::new(stream);
+
+    for line in reader.lines().filter_map(Result::ok) {
+        let command = match parse_command(line.trim()) {
+            Some(command) => command,
+            None => {
+                let _ = writeln!(writer, "ERROR unknown command: {}", line.trim());
+                continue;
+            }
+        };
+
+        let (reply_tx, reply_rx) = mpsc::channel();
+        if tx.send(ControlRequest { command, reply: reply_tx }).is_err() {
+            let _ = writeln!(writer, "ERROR supervisor is not accepting commands");
+            return;
+        }
+
+        match reply_rx.recv() {
+            Ok(response) => {
+                let _ = writeln!(writer, "{}", response);
+            }
+            Err(_) => {
+                let _ = writeln!(writer, "ERROR no response from supervisor");
+            }
+        }
+    }
+}
+
+fn parse_command(line: &str) -> Option<ControlCommand> {
+    let mut parts = line.split_whitespace();
+    match parts.next()? {
+        "status" => Some(ControlCommand::Status),
+        "reset" => Some(ControlCommand::Reset),
+        "restart" => parts.next().map(|name| ControlCommand::Restart(name.to_string())),
+        "stop" => Some(ControlCommand::Stop),
+        _ => None,
+    }
+}
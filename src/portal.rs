@@ -1,19 +1,23 @@
 use std::{
-    fs::File,
+    collections::HashMap,
     io::{BufRead, BufReader},
-    os::unix::io::{FromRawFd, RawFd},
+    os::unix::process::CommandExt,
     path::Path,
     process,
-    sync::mpsc,
+    sync::{Mutex, OnceLock},
     thread,
     time::Duration,
 };
 use nix::{
-    unistd::{dup2, ForkResult, fork, setsid},
+    errno::Errno,
+    sys::signal::{SigSet, Signal},
+    sys::signalfd::{SfdFlags, SignalFd},
+    sys::wait::{WaitPidFlag, WaitStatus, waitpid},
+    unistd::{Pid, setsid},
 };
 use crate::{
     logging::{LogLevel, log, format_pid},
-    process::{find_processes_by_name, kill_processes},
+    process::{find_processes_by_name, kill_process_tree},
 };
 
 pub const HYPR_PORTAL: &str = "/usr/lib/xdg-desktop-portal-hyprland";
@@ -54,13 +58,18 @@ pub fn kill_portal_processes() -> usize {
         log(LogLevel::Info, &format!("→ {} (PID: {})", name, pid));
     }
 
-    let killed = kill_processes(&processes, false);
-    
+    // Kill each matched portal's whole subtree (children before the portal
+    // itself), not just the PID we matched by name, so helper processes a
+    // portal spawned can't survive a reset and keep holding D-Bus names.
+    let killed: usize = processes.iter().map(|(pid, _)| kill_process_tree(*pid, false)).sum();
+
     // Check for remaining processes and force kill
     thread::sleep(Duration::from_millis(100));
     let remaining = find_portal_processes();
     if !remaining.is_empty() {
-        kill_processes(&remaining, true);
+        for (pid, _) in &remaining {
+            kill_process_tree(*pid, true);
+        }
     }
 
     if killed > 0 {
@@ -69,64 +78,129 @@ pub fn kill_portal_processes() -> usize {
     killed
 }
 
-pub fn spawn_portal(path: &str, name: &str) -> nix::Result<()> {
+// Blocks until a process whose comm/cmdline mentions `path`'s binary name
+// shows up (or `attempts` 100ms polls pass), so config-ordered services can
+// wait for a predecessor to be detected before starting their dependents.
+pub fn wait_for_service(path: &str, attempts: u32) -> bool {
+    let binary = Path::new(path).file_name().and_then(|n| n.to_str()).unwrap_or(path);
+    for _ in 0..attempts {
+        if !find_processes_by_name(binary, None).is_empty() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    false
+}
+
+fn io_err_to_nix(e: std::io::Error) -> Errno {
+    Errno::from_i32(e.raw_os_error().unwrap_or(nix::libc::EIO))
+}
+
+// Spawns the portal directly via `Command`, with `setsid` wired up through
+// `pre_exec` and stdout/stderr piped to the existing line-logging thread.
+// There is no forking wrapper to reap: the PID returned is the real portal
+// process, ready to hand straight to a supervisor's restart map. We never
+// call `Child::wait` here, so the caller must reap the exit via a `Reaper`
+// (see below) or the supervisor's own signalfd loop.
+pub fn spawn_portal(path: &str, name: &str, extra_args: &[String]) -> nix::Result<Option<Pid>> {
     if !Path::new(path).exists() {
         log(LogLevel::Error, &format!("Portal binary not found: {}", path));
-        return Ok(());
+        return Ok(None);
     }
 
     log(LogLevel::Info, &format!("Starting {}...", name));
-    
-    let (reader_rx, writer_tx) = nix::unistd::pipe()?;
-    let name = name.to_string();
-    let name_clone = name.clone();
-    
-    match unsafe { fork()? } {
-        ForkResult::Parent { child } => {
-            nix::unistd::close(writer_tx)?;
-            let (tx, rx) = mpsc::channel();
-            
-            thread::spawn(move || {
-                let file = unsafe { File::from_raw_fd(reader_rx) };
-                let reader = BufReader::new(file);
-                for line in reader.lines() {
-                    if let Ok(line) = line {
-                        tx.send(line).ok();
-                    }
-                }
-            });
-
-            thread::spawn(move || {
-                while let Ok(line) = rx.recv() {
-                    log(LogLevel::Process, &format!("[{}] {}", name_clone, line));
-                }
-            });
-
-            log(LogLevel::Success, &format!("Started {} {}", name, format_pid(child)));
+
+    let (reader, writer) = os_pipe::pipe().map_err(io_err_to_nix)?;
+    let writer_err = writer.try_clone().map_err(io_err_to_nix)?;
+
+    let mut command = process::Command::new(path);
+    command
+        .arg("-v")
+        .args(extra_args)
+        .stdin(process::Stdio::null())
+        .stdout(writer)
+        .stderr(writer_err);
+
+    unsafe {
+        command.pre_exec(|| {
+            setsid().map_err(|e| std::io::Error::from_raw_os_error(e as i32))?;
             Ok(())
-        },
-        ForkResult::Child => {
-            setsid()?;
-            
-            for fd in 0..=2 {
-                if fd != 1 && fd != 2 {
-                    let _ = nix::unistd::close(fd as RawFd);
-                } else {
-                    let _ = dup2(writer_tx, fd as RawFd);
-                }
-            }
-            let _ = nix::unistd::close(reader_rx);
-            
-            let err = process::Command::new(path)
-                .arg("-v")
-                .spawn()
-                .expect("failed to execute portal")
-                .wait();
-            
-            process::exit(match err {
-                Ok(status) => status.code().unwrap_or(1),
-                Err(_) => 1,
-            });
+        });
+    }
+
+    let child = command.spawn().map_err(io_err_to_nix)?;
+    let pid = Pid::from_raw(child.id() as i32);
+    // Drop the handle without waiting: the SIGCHLD reaper picks up the exit.
+    drop(child);
+
+    let name = name.to_string();
+    spawned_children().lock().unwrap().insert(pid, name.clone());
+    log(LogLevel::Success, &format!("Started {} {}", name, format_pid(pid)));
+
+    thread::spawn(move || {
+        for line in BufReader::new(reader).lines().map_while(Result::ok) {
+            log(LogLevel::Process, &format!("[{}] {}", name, line));
+        }
+    });
+
+    Ok(Some(pid))
+}
+
+static SPAWNED_CHILDREN: OnceLock<Mutex<HashMap<Pid, String>>> = OnceLock::new();
+
+fn spawned_children() -> &'static Mutex<HashMap<Pid, String>> {
+    SPAWNED_CHILDREN.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+// Reaps spawned portals without ever running a signal handler: SIGCHLD is
+// blocked on this thread and read back through a signalfd, so the actual
+// waitpid()/logging/mutex-locking work happens as plain code on whichever
+// thread calls `reap_pending`, never re-entrantly inside a signal handler.
+// (A `sigaction` handler here would be async-signal-unsafe: it could
+// interrupt `spawn_portal`'s own `spawned_children().lock()` on this same
+// thread and deadlock retaking that same non-reentrant mutex.)
+pub struct Reaper {
+    signal_fd: SignalFd,
+}
+
+// Installs the reaper by blocking SIGCHLD and opening a signalfd for it.
+// Safe to call more than once per thread.
+pub fn install_reaper() -> nix::Result<Reaper> {
+    let mut mask = SigSet::empty();
+    mask.add(Signal::SIGCHLD);
+    mask.thread_block()?;
+    let signal_fd = SignalFd::with_flags(&mask, SfdFlags::SFD_NONBLOCK)?;
+    Ok(Reaper { signal_fd })
+}
+
+impl Reaper {
+    // Non-blocking: drains any SIGCHLD notifications already queued on the
+    // signalfd and reaps the corresponding children. Ordinary (non-signal)
+    // code, so it's safe to sprinkle at convenient points in the one-shot
+    // reset flow instead of needing a dedicated event loop.
+    pub fn reap_pending(&self) {
+        while let Ok(Some(_)) = self.signal_fd.read_signal() {
+            reap_exited();
         }
     }
 }
+
+// Mirrors the mini-init reap_handler pattern: drain every exited child with
+// WNOHANG until none are left, logging which spawned portal exited and how.
+fn reap_exited() {
+    loop {
+        match waitpid(Some(Pid::from_raw(-1)), Some(WaitPidFlag::WNOHANG)) {
+            Ok(WaitStatus::Exited(pid, code)) => report_exit(pid, &format!("exited with code {}", code)),
+            Ok(WaitStatus::Signaled(pid, sig, _)) => report_exit(pid, &format!("killed by signal {:?}", sig)),
+            Ok(WaitStatus::StillAlive) | Err(Errno::ECHILD) => break,
+            Err(Errno::EINTR) => continue,
+            _ => break,
+        }
+    }
+}
+
+fn report_exit(pid: Pid, status: &str) {
+    let name = spawned_children().lock().unwrap().remove(&pid);
+    let name = name.as_deref().unwrap_or("unknown portal");
+    log(LogLevel::Warning, &format!("{} {} {}", name, format_pid(pid), status));
+}
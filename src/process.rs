@@ -1,10 +1,38 @@
-use std::{fs, os::unix::fs::MetadataExt};
+use std::{collections::HashMap, fs, os::unix::fs::MetadataExt, path::Path};
 use nix::{
     unistd::{self, Pid},
     sys::signal::{self, Signal},
 };
 use crate::logging::{LogLevel, log};
 
+// /proc/[pid]/comm is truncated by the kernel to 15 visible characters, so a
+// pattern longer than that (e.g. "xdg-desktop-portal-hyprland") can never be
+// found with a plain `comm.contains(name_pattern)` — comm is itself a prefix
+// of the real name, not the other way around. Treat comm as that possibly-
+// truncated prefix, and fall back to the full cmdline and the exe symlink
+// (as the old matcher did) for processes where comm doesn't line up at all.
+fn matches_name(proc_dir: &Path, name_pattern: &str) -> Option<String> {
+    let comm = fs::read_to_string(proc_dir.join("comm")).ok().map(|c| c.trim().to_string());
+
+    if let Some(comm) = &comm {
+        if !comm.is_empty() && (name_pattern.starts_with(comm.as_str()) || comm.contains(name_pattern)) {
+            return Some(comm.clone());
+        }
+    }
+
+    let cmdline_matches = fs::read_to_string(proc_dir.join("cmdline"))
+        .map(|cmdline| cmdline.split('\0').any(|arg| arg.contains(name_pattern)))
+        .unwrap_or(false);
+    if cmdline_matches {
+        return Some(comm.unwrap_or_else(|| name_pattern.to_string()));
+    }
+
+    fs::read_link(proc_dir.join("exe"))
+        .ok()
+        .and_then(|target| target.file_name().map(|n| n.to_string_lossy().into_owned()))
+        .filter(|file_name| file_name.contains(name_pattern))
+}
+
 pub fn find_processes_by_name(name_pattern: &str, args_pattern: Option<&str>) -> Vec<(Pid, String)> {
     let mut pids = Vec::new();
     let our_pid = std::process::id() as i32;
@@ -37,30 +65,20 @@ pub fn find_processes_by_name(name_pattern: &str, args_pattern: Option<&str>) ->
                 continue;
             }
 
-            let mut found = false;
-            let mut process_name = String::new();
-
-            // Check /proc/[pid]/comm
-            if let Ok(comm) = fs::read_to_string(entry.path().join("comm")) {
-                let comm = comm.trim();
-                if comm.contains(name_pattern) {
-                    if let Some(args_pattern) = args_pattern {
-                        if let Ok(cmdline) = fs::read_to_string(entry.path().join("cmdline")) {
-                            if cmdline.contains(args_pattern) {
-                                found = true;
-                                process_name = comm.to_string();
-                            }
-                        }
-                    } else {
-                        found = true;
-                        process_name = comm.to_string();
-                    }
+            let Some(process_name) = matches_name(&entry.path(), name_pattern) else {
+                continue;
+            };
+
+            if let Some(args_pattern) = args_pattern {
+                let cmdline_matches = fs::read_to_string(entry.path().join("cmdline"))
+                    .map(|cmdline| cmdline.split('\0').any(|arg| arg.contains(args_pattern)))
+                    .unwrap_or(false);
+                if !cmdline_matches {
+                    continue;
                 }
             }
 
-            if found {
-                pids.push((Pid::from_raw(pid), process_name));
-            }
+            pids.push((Pid::from_raw(pid), process_name));
         }
     }
     pids
@@ -69,8 +87,8 @@ pub fn find_processes_by_name(name_pattern: &str, args_pattern: Option<&str>) ->
 pub fn kill_processes(processes: &[(Pid, String)], force: bool) -> usize {
     let mut killed = 0;
     for (pid, name) in processes {
-        log(LogLevel::Warning, &format!("Sending {} to {} (PID: {})", 
-            if force { "SIGKILL" } else { "SIGTERM" }, 
+        log(LogLevel::Warning, &format!("Sending {} to {} (PID: {})",
+            if force { "SIGKILL" } else { "SIGTERM" },
             name, pid));
 
         let signal = if force { Signal::SIGKILL } else { Signal::SIGTERM };
@@ -80,3 +98,67 @@ pub fn kill_processes(processes: &[(Pid, String)], force: bool) -> usize {
     }
     killed
 }
+
+// Parses /proc/[pid]/stat's PPID (field 4). comm (field 2) is parenthesized
+// and can itself contain spaces or parens, so we split on the last ')'
+// rather than splitting the whole line on whitespace.
+fn parent_pid(pid: Pid) -> Option<Pid> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let after_comm = stat.rsplit_once(')')?.1;
+    let ppid: i32 = after_comm.split_whitespace().nth(1)?.parse().ok()?;
+    Some(Pid::from_raw(ppid))
+}
+
+fn owned_by_us(pid: Pid) -> bool {
+    fs::metadata(format!("/proc/{}", pid))
+        .map(|metadata| metadata.uid() == unistd::getuid().as_raw())
+        .unwrap_or(false)
+}
+
+// Builds a parent -> children map across every visible PID so a matched
+// portal's whole subtree can be found, not just the PID we matched by name.
+fn build_children_map() -> HashMap<Pid, Vec<Pid>> {
+    let mut map: HashMap<Pid, Vec<Pid>> = HashMap::new();
+
+    if let Ok(entries) = fs::read_dir("/proc") {
+        for entry in entries.filter_map(Result::ok) {
+            let pid = match entry.file_name().to_string_lossy().parse::<i32>() {
+                Ok(pid) => Pid::from_raw(pid),
+                Err(_) => continue,
+            };
+
+            if let Some(parent) = parent_pid(pid) {
+                map.entry(parent).or_default().push(pid);
+            }
+        }
+    }
+    map
+}
+
+// Signals an entire process subtree rooted at `root`, children before
+// parents, so that subprocesses spawned under a portal can't be reparented
+// to PID 1 and left running after the portal itself is killed. Only
+// processes we own are touched, same UID guard as find_processes_by_name.
+pub fn kill_process_tree(root: Pid, force: bool) -> usize {
+    let children_map = build_children_map();
+    let mut killed = 0;
+    kill_subtree(root, &children_map, force, &mut killed);
+    killed
+}
+
+fn kill_subtree(pid: Pid, children_map: &HashMap<Pid, Vec<Pid>>, force: bool, killed: &mut usize) {
+    if let Some(children) = children_map.get(&pid) {
+        for &child in children {
+            kill_subtree(child, children_map, force, killed);
+        }
+    }
+
+    if !owned_by_us(pid) {
+        return;
+    }
+
+    let signal = if force { Signal::SIGKILL } else { Signal::SIGTERM };
+    if signal::kill(pid, signal).is_ok() {
+        *killed += 1;
+    }
+}
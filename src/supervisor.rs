@@ -0,0 +1,319 @@
+use std::{
+    collections::{HashMap, HashSet},
+    os::unix::io::AsRawFd,
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use nix::{
+    errno::Errno,
+    poll::{PollFd, PollFlags, poll},
+    sys::{
+        signal::{self, SigSet, Signal},
+        signalfd::{SfdFlags, SignalFd},
+        wait::{WaitPidFlag, WaitStatus, waitpid},
+    },
+    unistd::Pid,
+};
+
+use crate::{
+    config::{self, ManagedService, RestartPolicy},
+    control::{self, ControlCommand, ControlRequest},
+    dbus::restart_dbus,
+    logging::{LogLevel, log, format_pid},
+    portal::{find_portal_processes, kill_portal_processes, spawn_portal},
+};
+
+// How long each poll() waits for a signalfd event before we check the
+// control socket channel and any due restarts.
+const POLL_TIMEOUT_MS: i32 = 250;
+
+const BASE_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+struct Managed {
+    service: ManagedService,
+    restart_attempts: u32,
+    last_restart: Option<Instant>,
+    // Set when an operator-issued `restart <name>` SIGTERM'd this service,
+    // so the reap it causes bypasses should_restart's policy/retry gate
+    // instead of being treated like an ordinary crash.
+    forced_restart: bool,
+}
+
+impl Managed {
+    fn new(service: ManagedService) -> Self {
+        Managed { service, restart_attempts: 0, last_restart: None, forced_restart: false }
+    }
+
+    // Exponential backoff from BASE_BACKOFF, capped at MAX_BACKOFF.
+    fn backoff(&self) -> Duration {
+        let exp = self.restart_attempts.min(6);
+        (BASE_BACKOFF * 2u32.pow(exp)).min(MAX_BACKOFF)
+    }
+
+    fn should_restart(&self, failed: bool) -> bool {
+        if self.restart_attempts >= self.service.max_retries {
+            return false;
+        }
+        match self.service.restart {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => failed,
+            RestartPolicy::Always => true,
+        }
+    }
+}
+
+// A crashed service waiting out its backoff before being respawned. Kept
+// separate from `children` (which only holds currently-running PIDs) so the
+// event loop never blocks a thread::sleep to wait out the delay.
+struct PendingRestart {
+    due: Instant,
+    managed: Managed,
+}
+
+// Run the tool as a persistent supervisor: spawn the configured services in
+// dependency order, then block on a signalfd for SIGCHLD/SIGTERM/SIGHUP
+// instead of polling `/proc` in a fixed-iteration loop. Crashed services are
+// restarted per their restart policy with exponential backoff, tracked as a
+// restart deadline rather than a blocking sleep so the loop keeps reaping
+// other children and answering the control socket in the meantime. SIGHUP
+// forces a full reset; SIGTERM shuts down.
+pub fn run() -> nix::Result<()> {
+    log(LogLevel::Info, "Starting portal supervisor");
+
+    let mut mask = SigSet::empty();
+    mask.add(Signal::SIGCHLD);
+    mask.add(Signal::SIGTERM);
+    mask.add(Signal::SIGHUP);
+    mask.thread_block()?;
+
+    let signal_fd = SignalFd::with_flags(&mask, SfdFlags::SFD_NONBLOCK)?;
+
+    let services = match config::topological_order(&config::load().services) {
+        Ok(services) => services,
+        Err(e) => {
+            log(LogLevel::Error, &format!("Invalid service config: {}", e));
+            return Ok(());
+        }
+    };
+
+    let mut children: HashMap<Pid, Managed> = HashMap::new();
+    let mut pending: Vec<PendingRestart> = Vec::new();
+    spawn_all(&mut children, services)?;
+
+    let (control_tx, control_rx) = mpsc::channel();
+    control::spawn_listener(control_tx)?;
+
+    let mut fds = [PollFd::new(signal_fd.as_raw_fd(), PollFlags::POLLIN)];
+    loop {
+        match poll(&mut fds, POLL_TIMEOUT_MS) {
+            Ok(_) => {}
+            Err(Errno::EINTR) => continue,
+            Err(e) => return Err(e),
+        }
+
+        if let Some(events) = fds[0].revents() {
+            if events.contains(PollFlags::POLLIN) {
+                while let Ok(Some(siginfo)) = signal_fd.read_signal() {
+                    match Signal::try_from(siginfo.ssi_signo as i32) {
+                        Ok(Signal::SIGCHLD) => reap_and_restart(&mut children, &mut pending)?,
+                        Ok(Signal::SIGHUP) => {
+                            log(LogLevel::Warning, "SIGHUP received, resetting all portals");
+                            reset_all(&mut children, &mut pending)?;
+                        }
+                        Ok(Signal::SIGTERM) => {
+                            log(LogLevel::Info, "SIGTERM received, shutting down supervisor");
+                            kill_portal_processes();
+                            return Ok(());
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        while let Ok(request) = control_rx.try_recv() {
+            if handle_control_request(&mut children, &mut pending, request)? {
+                return Ok(());
+            }
+        }
+
+        process_pending_restarts(&mut pending, &mut children)?;
+    }
+}
+
+// Runs a full kill + DBus restart + respawn cycle, as used by both SIGHUP
+// and the `reset` control-socket command. Anything still waiting out a
+// crash backoff is folded back into the respawn list instead of being lost.
+fn reset_all(children: &mut HashMap<Pid, Managed>, pending: &mut Vec<PendingRestart>) -> nix::Result<()> {
+    kill_portal_processes();
+    restart_dbus();
+
+    let mut services: Vec<ManagedService> = children.drain().map(|(_, managed)| managed.service).collect();
+    services.extend(pending.drain(..).map(|p| p.managed.service));
+    // Draining the map loses the startup order, so sort again before spawning.
+    let services = config::topological_order(&services).unwrap_or(services);
+    spawn_all(children, services)
+}
+
+// Handles one control-socket command, replying on its channel. Returns
+// `Ok(true)` when the supervisor should shut down (the `stop` command).
+fn handle_control_request(
+    children: &mut HashMap<Pid, Managed>,
+    pending: &mut Vec<PendingRestart>,
+    request: ControlRequest,
+) -> nix::Result<bool> {
+    let ControlRequest { command, reply } = request;
+
+    match command {
+        ControlCommand::Status => {
+            let mut lines = vec!["OK status".to_string()];
+            for (pid, managed) in children.iter() {
+                lines.push(format!("{} {} attempts={}", managed.service.name, format_pid(pid), managed.restart_attempts));
+            }
+            for p in pending.iter() {
+                lines.push(format!("{} pending-restart attempts={}", p.managed.service.name, p.managed.restart_attempts));
+            }
+            for (pid, name) in find_portal_processes() {
+                lines.push(format!("process {} {}", name, format_pid(pid)));
+            }
+            let _ = reply.send(lines.join("\n"));
+        }
+        ControlCommand::Reset => {
+            reset_all(children, pending)?;
+            let _ = reply.send("OK reset".to_string());
+        }
+        ControlCommand::Restart(name) => {
+            // Operator-initiated restarts bypass both the crash-restart
+            // retry counter and the restart policy (including `never`): a
+            // service that already exhausted its retries, or isn't meant to
+            // come back on its own, must still come back when explicitly
+            // asked for.
+            if let Some(index) = pending.iter().position(|p| p.managed.service.name == name) {
+                let mut entry = pending.remove(index);
+                entry.managed.restart_attempts = 0;
+                entry.due = Instant::now();
+                pending.push(entry);
+                let _ = reply.send(format!("OK restarting {}", name));
+            } else if let Some(pid) = children.iter().find(|(_, m)| m.service.name == name).map(|(pid, _)| *pid) {
+                if let Some(managed) = children.get_mut(&pid) {
+                    managed.restart_attempts = 0;
+                    managed.forced_restart = true;
+                }
+                let _ = signal::kill(pid, Signal::SIGTERM);
+                let _ = reply.send(format!("OK restarting {}", name));
+            } else {
+                let _ = reply.send(format!("ERROR no such service: {}", name));
+            }
+        }
+        ControlCommand::Stop => {
+            let _ = reply.send("OK stopping".to_string());
+            kill_portal_processes();
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// Spawns each service in dependency order, gating a service's start on every
+// name in its `after` list having actually come up (an unknown name, not
+// present in this config, can't be waited on and is treated as satisfied).
+// The gate-and-retry spawn itself lives in `config::start_service`, shared
+// with `run_reset`'s one-shot startup. A service that fails to come up is
+// left unspawned, which in turn blocks anything depending on it.
+fn spawn_all(children: &mut HashMap<Pid, Managed>, services: Vec<ManagedService>) -> nix::Result<()> {
+    let names: HashSet<String> = services.iter().map(|s| s.name.clone()).collect();
+    let mut succeeded: HashSet<String> = HashSet::new();
+
+    for service in services {
+        let name = service.name.clone();
+
+        if let Some(dep) = service.after.iter().find(|dep| names.contains(*dep) && !succeeded.contains(*dep)) {
+            log(LogLevel::Error, &format!("Skipping {}: dependency {} did not start", name, dep));
+            continue;
+        }
+
+        match config::start_service(&service)? {
+            Some(pid) => {
+                succeeded.insert(name);
+                children.insert(pid, Managed::new(service));
+            }
+            None => {
+                log(LogLevel::Error, &format!(
+                    "{} failed to start after up to {} attempt(s), dependents will be skipped",
+                    name, config::SERVICE_START_ATTEMPTS
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn reap_and_restart(children: &mut HashMap<Pid, Managed>, pending: &mut Vec<PendingRestart>) -> nix::Result<()> {
+    loop {
+        match waitpid(Some(Pid::from_raw(-1)), Some(WaitPidFlag::WNOHANG)) {
+            Ok(WaitStatus::StillAlive) => break,
+            Ok(WaitStatus::Exited(pid, code)) => {
+                log(LogLevel::Warning, &format!("Child {} exited with code {}", pid, code));
+                queue_restart(children, pending, pid, code != 0);
+            }
+            Ok(WaitStatus::Signaled(pid, sig, _)) => {
+                log(LogLevel::Warning, &format!("Child {} killed by signal {:?}", pid, sig));
+                queue_restart(children, pending, pid, true);
+            }
+            Ok(_) => continue,
+            Err(Errno::ECHILD) => break,
+            Err(Errno::EINTR) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+// Decides whether a just-reaped child should come back, and if so records
+// a restart deadline instead of blocking the event loop on thread::sleep.
+// An operator-forced restart (see ControlCommand::Restart) bypasses
+// should_restart entirely, including a `never` restart policy: a manual
+// restart must never silently turn into a stop.
+fn queue_restart(children: &mut HashMap<Pid, Managed>, pending: &mut Vec<PendingRestart>, pid: Pid, failed: bool) {
+    let Some(mut managed) = children.remove(&pid) else {
+        return;
+    };
+
+    let forced = managed.forced_restart;
+    managed.forced_restart = false;
+
+    if !forced && !managed.should_restart(failed) {
+        log(LogLevel::Error, &format!("{} stopped (restart policy: no further attempts)", managed.service.name));
+        return;
+    }
+
+    let backoff = managed.backoff();
+    log(LogLevel::Info, &format!(
+        "Restarting {} in {:?} (attempt {}/{})",
+        managed.service.name, backoff, managed.restart_attempts + 1, managed.service.max_retries
+    ));
+    managed.restart_attempts += 1;
+    pending.push(PendingRestart { due: Instant::now() + backoff, managed });
+}
+
+// Respawns every pending restart whose backoff has elapsed. Called once per
+// event-loop tick so a restart is delayed by at most one POLL_TIMEOUT_MS.
+fn process_pending_restarts(pending: &mut Vec<PendingRestart>, children: &mut HashMap<Pid, Managed>) -> nix::Result<()> {
+    let now = Instant::now();
+    let mut index = 0;
+    while index < pending.len() {
+        if pending[index].due > now {
+            index += 1;
+            continue;
+        }
+
+        let mut entry = pending.remove(index);
+        entry.managed.last_restart = Some(now);
+        if let Some(new_pid) = spawn_portal(&entry.managed.service.path, &entry.managed.service.name, &entry.managed.service.args)? {
+            children.insert(new_pid, entry.managed);
+        }
+    }
+    Ok(())
+}